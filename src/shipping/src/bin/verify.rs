@@ -0,0 +1,127 @@
+//! Standalone provider verifier.
+//!
+//! Runs the same [`run_verification`] core the contract test uses, but driven
+//! entirely from command-line flags and environment variables so it can be
+//! pointed at an already-deployed provider from a CI job. Exits non-zero when
+//! verification fails.
+//!
+//! ```text
+//! verify --provider-name ShippingService --host shipping.svc --port 8080 \
+//!        --pact-file pacts/Frontend-ShippingService.json \
+//!        --filter-description "a quote request"
+//! ```
+//!
+//! With `PACT_BROKER_BASE_URL` set (and no `--pact-file`) the pacts are pulled
+//! from the broker instead; results are published back when `GIT_COMMIT` is
+//! set.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use pact_verifier::callback_executors::HttpRequestProviderStateExecutor;
+use pact_verifier::FilterInfo;
+
+use shipping::verifier::{run_verification, BrokerConfig, PactSourceConfig, VerifyConfig};
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut provider_name = "ShippingService".to_string();
+    let mut host = "127.0.0.1".to_string();
+    let mut port: u16 = 8080;
+    let mut scheme = "http".to_string();
+    let mut pact_file: Option<PathBuf> = None;
+    let mut filter_description: Option<String> = None;
+    let mut filter_state: Option<String> = None;
+    let mut request_timeout: u64 = 5000;
+    let mut coloured_output = true;
+    let mut verify_messages = false;
+    let mut state_change_url: Option<String> = std::env::var("PACT_STATE_CHANGE_URL").ok();
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().unwrap_or_else(|| fail(&format!("missing value for {arg}")));
+        match arg.as_str() {
+            "--provider-name" => provider_name = value(),
+            "--host" => host = value(),
+            "--port" => port = value().parse().unwrap_or_else(|_| fail("--port must be a number")),
+            "--scheme" => scheme = value(),
+            "--pact-file" => pact_file = Some(PathBuf::from(value())),
+            "--filter-description" => filter_description = Some(value()),
+            "--filter-state" => filter_state = Some(value()),
+            "--timeout" => {
+                request_timeout = value().parse().unwrap_or_else(|_| fail("--timeout must be a number"))
+            }
+            "--no-color" => coloured_output = false,
+            "--messages" => verify_messages = true,
+            "--state-change-url" => state_change_url = Some(value()),
+            "-h" | "--help" => {
+                print_usage();
+                return;
+            }
+            other => fail(&format!("unknown flag: {other}")),
+        }
+    }
+
+    // A single interaction can be selected by description, by provider state, or
+    // by both; otherwise the whole pact is verified.
+    let filter = match (filter_description, filter_state) {
+        (Some(desc), Some(state)) => FilterInfo::DescriptionAndState(desc, state),
+        (Some(desc), None) => FilterInfo::Description(desc),
+        (None, Some(state)) => FilterInfo::State(state),
+        (None, None) => FilterInfo::None,
+    };
+
+    let source = match pact_file {
+        Some(path) => PactSourceConfig::File(path),
+        None => PactSourceConfig::Broker(BrokerConfig::from_env()),
+    };
+
+    // A deployed provider manages its own state via a state-change endpoint.
+    // Build from `Default` and set only the public `state_change_url` field, so
+    // we never name the remaining (possibly private) fields that a
+    // struct-update literal would require to be accessible.
+    let mut state_executor = HttpRequestProviderStateExecutor::default();
+    state_executor.state_change_url = state_change_url;
+    let provider_states = Arc::new(state_executor);
+
+    let config = VerifyConfig {
+        provider_name,
+        provider_host: host,
+        provider_port: port,
+        provider_scheme: scheme,
+        source,
+        filter,
+        request_timeout,
+        coloured_output,
+        verify_messages,
+        provider_states,
+    };
+
+    match run_verification(config).await {
+        Ok(result) if result.result => std::process::exit(0),
+        Ok(_) => {
+            eprintln!("Pact verification failed, see output above for details");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Pact verification process errored: {err}");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    print_usage();
+    std::process::exit(2);
+}
+
+fn print_usage() {
+    eprintln!(
+        "usage: verify [--provider-name NAME] [--host HOST] [--port PORT] [--scheme SCHEME]\n\
+         \x20             [--pact-file PATH] [--filter-description DESC] [--filter-state STATE]\n\
+         \x20             [--timeout MS] [--no-color] [--messages] [--state-change-url URL]"
+    );
+}
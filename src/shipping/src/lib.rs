@@ -0,0 +1,2 @@
+pub mod shipping_service;
+pub mod verifier;
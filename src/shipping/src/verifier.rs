@@ -0,0 +1,403 @@
+//! Reusable provider-verification core shared by the contract-verification test
+//! and the standalone `verify` binary.
+//!
+//! [`run_verification`] drives `pact_verifier` against an already-running
+//! provider described by [`VerifyConfig`]; it neither starts nor stops the
+//! provider, so the same flow works against a test server or a deployed
+//! service.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use pact_models::http_utils::HttpAuth;
+use pact_models::pact_broker::Link;
+use pact_models::prelude::ProviderState;
+use pact_models::selectors::ConsumerVersionSelector;
+use pact_verifier::callback_executors::{NullRequestFilterExecutor, ProviderStateExecutor};
+use pact_verifier::{
+    FilterInfo, PactSource, ProviderInfo, ProviderTransport, PublishOptions, VerificationExecResult,
+    VerificationOptions,
+};
+use serde_json::Value;
+
+/// Where [`run_verification`] should pull the consumer pacts from.
+///
+/// `File` points at a pact committed to the repo, which is what the demo test
+/// has always done. `Broker` pulls the consumer pacts for the provider from a
+/// running Pact Broker and publishes the verification results back so the
+/// broker's can-i-deploy matrix reflects this run.
+pub enum PactSourceConfig {
+    File(PathBuf),
+    Broker(BrokerConfig),
+}
+
+/// Connection and selection details for a Pact Broker source.
+pub struct BrokerConfig {
+    /// Base URL of the broker, e.g. `https://pacts.example.com`.
+    pub url: String,
+    /// Optional broker authentication (bearer token or basic auth).
+    pub auth: Option<HttpAuth>,
+    /// Consumer version selectors describing which pacts to pull.
+    pub selectors: Vec<ConsumerVersionSelector>,
+    /// Enable pending pacts: mismatches on pacts the broker flags as pending
+    /// are reported but do not flip `result.result` to `false`, so a new
+    /// consumer expectation can be integrated without breaking the build.
+    pub enable_pending: bool,
+    /// When set, also pull work-in-progress pacts changed after this timestamp
+    /// (RFC3339), which are treated as pending for this run.
+    pub include_wip_pacts_since: Option<String>,
+    /// Options for publishing verification results back to the broker. When
+    /// `None` the pacts are fetched but results are not published.
+    pub publish: Option<PublishConfig>,
+}
+
+/// Details published back to the broker after a verification run.
+pub struct PublishConfig {
+    /// Application version of the provider under verification, typically the
+    /// git commit the provider was built from.
+    pub provider_version: String,
+    /// Branch the provider was built from, used to key the can-i-deploy matrix.
+    pub provider_branch: Option<String>,
+}
+
+impl BrokerConfig {
+    /// Build a broker configuration from the conventional Pact Broker
+    /// environment variables, suitable for a CI job.
+    ///
+    /// `PACT_BROKER_BASE_URL` selects the broker. Authentication is taken from
+    /// `PACT_BROKER_TOKEN`, or failing that `PACT_BROKER_USERNAME` /
+    /// `PACT_BROKER_PASSWORD`. Results are published using `GIT_COMMIT` as the
+    /// provider version and `GIT_BRANCH` as the provider branch.
+    pub fn from_env() -> Self {
+        let url = std::env::var("PACT_BROKER_BASE_URL")
+            .expect("PACT_BROKER_BASE_URL must be set for broker verification");
+
+        let auth = match std::env::var("PACT_BROKER_TOKEN") {
+            Ok(token) if !token.is_empty() => Some(HttpAuth::Token(token)),
+            _ => std::env::var("PACT_BROKER_USERNAME").ok().map(|user| {
+                HttpAuth::User(user, std::env::var("PACT_BROKER_PASSWORD").ok())
+            }),
+        };
+
+        let provider_branch = std::env::var("GIT_BRANCH").ok();
+        let publish = std::env::var("GIT_COMMIT").ok().map(|provider_version| PublishConfig {
+            provider_version,
+            provider_branch: provider_branch.clone(),
+        });
+
+        // Pull the latest pact from the consumer's main branch; a caller can
+        // widen this by replacing `selectors` on the returned config.
+        let selectors = vec![ConsumerVersionSelector {
+            main_branch: Some(true),
+            ..ConsumerVersionSelector::default()
+        }];
+
+        // Pending is on by default so a freshly published consumer contract
+        // does not fail the provider pipeline; WIP pulls in pacts changed since
+        // the given timestamp when requested.
+        let enable_pending = std::env::var("PACT_ENABLE_PENDING")
+            .map(|v| v != "false")
+            .unwrap_or(true);
+        let include_wip_pacts_since = std::env::var("PACT_INCLUDE_WIP_SINCE")
+            .ok()
+            .filter(|v| !v.is_empty());
+
+        BrokerConfig {
+            url,
+            auth,
+            selectors,
+            enable_pending,
+            include_wip_pacts_since,
+            publish,
+        }
+    }
+}
+
+/// Shared slice of the shipping provider that provider-state handlers mutate to
+/// put the service into the state a consumer interaction expects before it is
+/// replayed.
+#[derive(Clone)]
+pub struct ProviderContext {
+    /// In-memory order store keyed by order id, seeded by "order exists" states
+    /// so that `ship_order` can find the order it is asked to dispatch.
+    pub orders: Arc<Mutex<HashMap<String, Value>>>,
+    /// Price string served by the stub quote service. The "quote service is
+    /// reachable" state overwrites it (with its `price` parameter, when given)
+    /// so `get_quote` sees the price the interaction assumes.
+    pub quote_price: Arc<Mutex<String>>,
+}
+
+impl ProviderContext {
+    pub fn new() -> Self {
+        ProviderContext {
+            orders: Arc::new(Mutex::new(HashMap::new())),
+            quote_price: Arc::new(Mutex::new("5.99".to_string())),
+        }
+    }
+}
+
+impl Default for ProviderContext {
+    fn default() -> Self {
+        ProviderContext::new()
+    }
+}
+
+type StateResult = anyhow::Result<HashMap<String, Value>>;
+type StateFuture = Pin<Box<dyn Future<Output = StateResult> + Send>>;
+type StateHandler = Box<dyn Fn(ProviderContext, ProviderState) -> StateFuture + Send + Sync>;
+
+/// A setup closure and an optional matching teardown closure for one named
+/// provider state.
+struct RegisteredState {
+    setup: StateHandler,
+    teardown: Option<StateHandler>,
+}
+
+/// Provider state executor backed by the shipping service.
+///
+/// Dispatches on `provider_state.name` to a registered setup or teardown
+/// closure; the `setup` flag chooses between them. Setup closures seed the
+/// in-memory order store and reconfigure the stub quote service, and may return
+/// generated values (such as a created order id) so they can be injected into
+/// request paths via provider-state generators. An unknown state is a hard
+/// error rather than a silent pass, so interactions are never verified against
+/// an unprepared provider.
+pub struct ShippingProviderStates {
+    context: ProviderContext,
+    handlers: HashMap<String, RegisteredState>,
+}
+
+impl ShippingProviderStates {
+    pub fn new(context: ProviderContext) -> Self {
+        let mut states = ShippingProviderStates { context, handlers: HashMap::new() };
+
+        // "order 123 exists": seed the order store so `ship_order` can find the
+        // order, returning the created id for injection into the request path.
+        states.register(
+            "order 123 exists",
+            |ctx, state| {
+                Box::pin(async move {
+                    let id = state
+                        .params
+                        .get("id")
+                        .and_then(|v| v.as_str().map(String::from))
+                        .unwrap_or_else(|| "123".to_string());
+                    ctx.orders.lock().unwrap().insert(
+                        id.clone(),
+                        serde_json::json!({ "id": id, "status": "created" }),
+                    );
+                    Ok(HashMap::from([("orderId".to_string(), Value::from(id))]))
+                })
+            },
+            Some(Box::new(|ctx: ProviderContext, state: ProviderState| {
+                Box::pin(async move {
+                    let id = state
+                        .params
+                        .get("id")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("123")
+                        .to_string();
+                    ctx.orders.lock().unwrap().remove(&id);
+                    Ok(HashMap::new())
+                }) as StateFuture
+            }) as StateHandler),
+        );
+
+        // "quote service is reachable": point the stub quote service at a fixed
+        // price, optionally taken from a `price` parameter for "quote is $X".
+        // Teardown restores the default so a price set by one interaction does
+        // not leak into later interactions that assume "5.99".
+        states.register(
+            "quote service is reachable",
+            |ctx, state| {
+                Box::pin(async move {
+                    let price = state
+                        .params
+                        .get("price")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("5.99")
+                        .to_string();
+                    *ctx.quote_price.lock().unwrap() = price;
+                    Ok(HashMap::new())
+                })
+            },
+            Some(Box::new(|ctx: ProviderContext, _state: ProviderState| {
+                Box::pin(async move {
+                    *ctx.quote_price.lock().unwrap() = "5.99".to_string();
+                    Ok(HashMap::new())
+                }) as StateFuture
+            }) as StateHandler),
+        );
+
+        states
+    }
+
+    /// Register a setup closure and an optional, already-erased teardown
+    /// closure for a state name. Teardown is taken as `Option<StateHandler>` so
+    /// a `None` caller does not leave the closure type parameter uninferable.
+    fn register<S>(&mut self, name: &str, setup: S, teardown: Option<StateHandler>)
+    where
+        S: Fn(ProviderContext, ProviderState) -> StateFuture + Send + Sync + 'static,
+    {
+        self.handlers.insert(
+            name.to_string(),
+            RegisteredState {
+                setup: Box::new(setup),
+                teardown,
+            },
+        );
+    }
+}
+
+#[async_trait]
+impl ProviderStateExecutor for ShippingProviderStates {
+    async fn call(
+        self: Arc<Self>,
+        _interaction_id: Option<String>,
+        provider_state: &ProviderState,
+        setup: bool,
+        _client: Option<&reqwest::Client>,
+    ) -> anyhow::Result<HashMap<String, Value>> {
+        match self.handlers.get(&provider_state.name) {
+            Some(handler) if setup => {
+                (handler.setup)(self.context.clone(), provider_state.clone()).await
+            }
+            Some(handler) => match &handler.teardown {
+                Some(teardown) => {
+                    (teardown)(self.context.clone(), provider_state.clone()).await
+                }
+                None => Ok(HashMap::new()),
+            },
+            None => Err(anyhow::anyhow!(
+                "No provider state handler registered for '{}'",
+                provider_state.name
+            )),
+        }
+    }
+
+    fn teardown(self: &Self) -> bool {
+        true
+    }
+}
+
+/// Everything [`run_verification`] needs to verify an already-running shipping
+/// provider against a set of consumer pacts, independent of how the provider
+/// server was started.
+pub struct VerifyConfig<S: ProviderStateExecutor> {
+    /// Provider name as registered in the broker.
+    pub provider_name: String,
+    /// Host the provider server is listening on.
+    pub provider_host: String,
+    /// Port the provider server is listening on.
+    pub provider_port: u16,
+    /// Scheme the provider server speaks.
+    pub provider_scheme: String,
+    /// Where to read the consumer pacts from.
+    pub source: PactSourceConfig,
+    /// Restricts verification to a single interaction by description or
+    /// provider state; [`FilterInfo::None`] verifies every interaction.
+    pub filter: FilterInfo,
+    /// Per-request timeout in milliseconds.
+    pub request_timeout: u64,
+    /// Whether to colour the verifier output.
+    pub coloured_output: bool,
+    /// Whether to also verify V4 message pacts via the `"message"` transport;
+    /// the message-producer endpoint is served on the provider port.
+    pub verify_messages: bool,
+    /// Executor that prepares the provider for each interaction's state.
+    pub provider_states: Arc<S>,
+}
+
+/// Verify the shipping provider against its consumer pacts.
+///
+/// Selects a `PactSource::File` or `PactSource::BrokerWithDynamicConfiguration`
+/// based on `config.source`; the broker variant pulls the consumer pacts by
+/// provider name and, when publish options are supplied, reports the results
+/// back to the broker.
+pub async fn run_verification<S>(
+    config: VerifyConfig<S>,
+) -> anyhow::Result<VerificationExecResult>
+where
+    S: ProviderStateExecutor + Send + Sync + 'static,
+{
+    let mut transports = vec![ProviderTransport {
+        transport: "http".to_string(),
+        port: Some(config.provider_port),
+        path: None,
+        scheme: Some(config.provider_scheme.clone()),
+    }];
+    if config.verify_messages {
+        // Messages are fetched from the producer endpoint on the provider port.
+        transports.push(ProviderTransport {
+            transport: "message".to_string(),
+            port: Some(config.provider_port),
+            path: Some("/pact-messages".to_string()),
+            scheme: Some(config.provider_scheme.clone()),
+        });
+    }
+
+    let provider_info = ProviderInfo {
+        name: config.provider_name.clone(),
+        host: config.provider_host.clone(),
+        transports,
+        ..Default::default()
+    };
+
+    let (sources, publish_options) = match config.source {
+        PactSourceConfig::File(path) => {
+            (vec![PactSource::File(path.to_string_lossy().to_string())], None)
+        }
+        PactSourceConfig::Broker(broker) => {
+            let publish_options = broker.publish.as_ref().map(|publish| PublishOptions {
+                provider_version: Some(publish.provider_version.clone()),
+                build_url: std::env::var("BUILD_URL").ok(),
+                provider_tags: Vec::new(),
+                provider_branch: publish.provider_branch.clone(),
+            });
+
+            let source = PactSource::BrokerWithDynamicConfiguration {
+                provider_name: config.provider_name.clone(),
+                broker_url: broker.url,
+                enable_pending: broker.enable_pending,
+                include_wip_pacts_since: broker.include_wip_pacts_since,
+                provider_tags: Vec::new(),
+                provider_branch: broker
+                    .publish
+                    .as_ref()
+                    .and_then(|publish| publish.provider_branch.clone()),
+                selectors: broker.selectors,
+                auth: broker.auth,
+                links: Vec::<Link>::new(),
+            };
+
+            (vec![source], publish_options)
+        }
+    };
+
+    let verification_options = VerificationOptions::<NullRequestFilterExecutor> {
+        request_filter: None,
+        disable_ssl_verification: false,
+        request_timeout: config.request_timeout,
+        custom_headers: Default::default(),
+        coloured_output: config.coloured_output,
+        no_pacts_is_error: true,
+        exit_on_first_failure: false,
+        run_last_failed_only: false,
+    };
+
+    pact_verifier::verify_provider_async(
+        provider_info,
+        sources,
+        config.filter,
+        vec![],
+        &verification_options,
+        publish_options.as_ref(),
+        &config.provider_states,
+        None,
+    )
+    .await
+}
@@ -1,113 +1,237 @@
-use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use actix_web::{App, HttpServer};
-use async_trait::async_trait;
-use pact_verifier::callback_executors::{NullRequestFilterExecutor, ProviderStateExecutor};
-use pact_verifier::{FilterInfo, ProviderInfo, ProviderTransport, VerificationOptions, PactSource};
+use actix_web::dev::ServerHandle;
+use actix_web::{post, web, App, HttpResponse, HttpServer};
 use portpicker::pick_unused_port;
 
 use shipping::shipping_service::{get_quote, ship_order};
-use serde_json::Value;
-use pact_models::prelude::ProviderState;
+use shipping::verifier::{
+    run_verification, BrokerConfig, PactSourceConfig, ProviderContext, ShippingProviderStates,
+    VerifyConfig,
+};
+use pact_verifier::FilterInfo;
+
+/// A provider state the verifier attaches to a message interaction.
+#[derive(serde::Deserialize)]
+struct MessageProviderState {
+    name: String,
+}
+
+/// Request body the pact verifier posts to the message-producer endpoint when
+/// checking a V4 message interaction.
+#[derive(serde::Deserialize)]
+struct MessageProducerRequest {
+    /// Description of the message interaction being verified.
+    #[serde(default)]
+    description: String,
+    /// Provider states declared on the message interaction.
+    #[serde(default, rename = "providerStates")]
+    provider_states: Vec<MessageProviderState>,
+}
+
+/// Provider state that selects the "shipment dispatched" event payload.
+const SHIPMENT_DISPATCHED_STATE: &str = "a shipment has been dispatched";
+
+/// Message-producer endpoint used by V4 message-pact verification.
+///
+/// Dispatches on the interaction's provider state (falling back to its
+/// description) to the event payload the shipping service would publish.
+/// Registering it under the `"message"` transport lets a single verification
+/// run cover both the synchronous shipping API and the shipment events the
+/// service emits. An unrecognised message is a `404` rather than an empty body
+/// so a mismatched key fails loudly instead of silently passing.
+#[post("/pact-messages")]
+async fn pact_message_producer(req: web::Json<MessageProducerRequest>) -> HttpResponse {
+    let selects_dispatch = req
+        .provider_states
+        .iter()
+        .any(|state| state.name == SHIPMENT_DISPATCHED_STATE)
+        || req.description.contains("shipment dispatched");
+
+    if selects_dispatch {
+        HttpResponse::Ok().json(serde_json::json!({
+            "type": "shipment.dispatched",
+            "orderId": "123",
+            "carrier": "UPS",
+            "trackingNumber": "1Z999AA10123456784",
+        }))
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+/// Returns whether the pact at `path` carries at least one asynchronous message
+/// interaction, so the `"message"` transport is registered only when there is a
+/// message to verify.
+fn pact_has_messages(path: &std::path::Path) -> bool {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return false;
+    };
+    let Ok(pact) = serde_json::from_str::<serde_json::Value>(&contents) else {
+        return false;
+    };
+
+    // V3 message pacts carry a top-level "messages" array; V4 pacts tag each
+    // entry in "interactions" with a message type.
+    let has_v3 = pact
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .is_some_and(|messages| !messages.is_empty());
+    let has_v4 = pact
+        .get("interactions")
+        .and_then(|i| i.as_array())
+        .is_some_and(|interactions| {
+            interactions.iter().any(|interaction| {
+                interaction
+                    .get("type")
+                    .and_then(|t| t.as_str())
+                    .is_some_and(|t| t.eq_ignore_ascii_case("asynchronous/messages"))
+            })
+        });
+
+    has_v3 || has_v4
+}
+
+/// Guard that owns the shipping server and quote-stub tasks and tears both down
+/// cleanly. On the happy path [`ServerGuard::shutdown`] drains in-flight
+/// requests and awaits the tasks to completion; if verification panics first,
+/// `Drop` still signals both servers to stop so their ports are released rather
+/// than leaked across repeated test runs.
+struct ServerGuard {
+    shipping: ServerHandle,
+    quote: ServerHandle,
+    shipping_task: Option<tokio::task::JoinHandle<std::io::Result<()>>>,
+    quote_task: Option<tokio::task::JoinHandle<std::io::Result<()>>>,
+}
+
+impl ServerGuard {
+    /// Gracefully stop both servers, draining connections, and await their
+    /// tasks to completion.
+    async fn shutdown(mut self) {
+        self.shipping.stop(true).await;
+        self.quote.stop(true).await;
+        if let Some(task) = self.shipping_task.take() {
+            let _ = task.await;
+        }
+        if let Some(task) = self.quote_task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        // `shutdown` consumes the tasks on the happy path; if they are still
+        // here we are unwinding from a panic. Block on the stop futures (rather
+        // than spawning a detached task the unwinding runtime may drop before
+        // it runs) so the bound ports are actually released before we return.
+        let shipping_task = self.shipping_task.take();
+        let quote_task = self.quote_task.take();
+        if shipping_task.is_none() && quote_task.is_none() {
+            return;
+        }
+
+        let shipping = self.shipping.clone();
+        let quote = self.quote.clone();
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async move {
+                shipping.stop(true).await;
+                quote.stop(true).await;
+                if let Some(task) = shipping_task {
+                    let _ = task.await;
+                }
+                if let Some(task) = quote_task {
+                    let _ = task.await;
+                }
+            });
+        });
+    }
+}
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn verify_shipping_pact() {
     // Pick ports for provider and quote stub
     let port = pick_unused_port().expect("No free port for provider");
     let quote_port = pick_unused_port().expect("No free port for quote service");
-    
+
+    // Shared provider context: the order store and the price the stub quote
+    // service serves, both driven by the provider-state executor.
+    let context = ProviderContext::new();
+
     // Start the shipping service HTTP server in the background.
-    // Start stub quote service that returns fixed float value
-    let quote_server = HttpServer::new(|| App::new().default_service(actix_web::web::to(|| async { "5.99" })))
-        .bind(("127.0.0.1", quote_port)).expect("bind quote").run();
-    let _quote_handle = tokio::spawn(quote_server);
+    // Start stub quote service that returns the currently configured price.
+    let stub_price = context.quote_price.clone();
+    let quote_server = HttpServer::new(move || {
+        let stub_price = stub_price.clone();
+        App::new().default_service(actix_web::web::to(move || {
+            let stub_price = stub_price.clone();
+            async move { stub_price.lock().unwrap().clone() }
+        }))
+    })
+    .bind(("127.0.0.1", quote_port)).expect("bind quote").run();
+    let quote_handle = quote_server.handle();
+    let quote_task = tokio::spawn(quote_server);
 
     // point provider to stub quote service
     std::env::set_var("QUOTE_ADDR", format!("http://127.0.0.1:{}", quote_port));
 
-    let server = HttpServer::new(|| {
+    // Share the same order store the provider-state executor seeds into the
+    // provider app so `ship_order` can find orders created by "order exists".
+    let orders = web::Data::from(context.orders.clone());
+    let server = HttpServer::new(move || {
         App::new()
+            .app_data(orders.clone())
             .service(get_quote)
             .service(ship_order)
+            .service(pact_message_producer)
     })
     .bind(("127.0.0.1", port)).expect("failed to bind port")
     .run();
 
-    let srv_handle = tokio::spawn(server);
-
-    // drop _quote_handle when test ends
-    let _ = _quote_handle;
-
-    // Build ProviderInfo for pact verifier.
-    let provider_info = ProviderInfo {
-        name: "ShippingService".to_string(),
-        host: "127.0.0.1".to_string(),
-        transports: vec![ProviderTransport {
-            transport: "http".to_string(),
-            port: Some(port),
-            path: None,
-            scheme: Some("http".to_string()),
-        }],
-        ..Default::default()
+    let shipping_handle = server.handle();
+    let shipping_task = tokio::spawn(server);
+
+    // Own both servers through a guard so a panic during verification still
+    // tears them down instead of leaking bound ports.
+    let guard = ServerGuard {
+        shipping: shipping_handle,
+        quote: quote_handle,
+        shipping_task: Some(shipping_task),
+        quote_task: Some(quote_task),
     };
 
-    // Locate the pact file relative to the crate root.
-    let pact_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../frontend/pacts/Frontend-ShippingService.json");
+    // Locate the pact file relative to the crate root, unless a broker is
+    // configured via PACT_BROKER_BASE_URL. Register the message transport only
+    // when the pact actually carries a message interaction; pulling from a
+    // broker we can't know ahead of time, so we verify messages there too.
+    let (source, verify_messages) = if std::env::var("PACT_BROKER_BASE_URL").is_ok() {
+        (PactSourceConfig::Broker(BrokerConfig::from_env()), true)
+    } else {
+        let pact_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../frontend/pacts/Frontend-ShippingService.json");
+        let has_messages = pact_has_messages(&pact_path);
+        (PactSourceConfig::File(pact_path), has_messages)
+    };
 
-    // Prepare options â€“ we don't need any special filters or headers.
-    let verification_options = VerificationOptions::<NullRequestFilterExecutor> {
-        request_filter: None,
-        disable_ssl_verification: false,
+    let result = run_verification(VerifyConfig {
+        provider_name: "ShippingService".to_string(),
+        provider_host: "127.0.0.1".to_string(),
+        provider_port: port,
+        provider_scheme: "http".to_string(),
+        source,
+        filter: FilterInfo::None,
         request_timeout: 5000,
-        custom_headers: Default::default(),
         coloured_output: false,
-        no_pacts_is_error: true,
-        exit_on_first_failure: false,
-        run_last_failed_only: false,
-    };
-
-    // Simple provider state executor that does nothing and always succeeds.
-    #[derive(Debug, Clone)]
-    struct NoOpProviderState;
-
-    #[async_trait]
-    impl ProviderStateExecutor for NoOpProviderState {
-        async fn call(
-            self: Arc<Self>,
-            _interaction_id: Option<String>,
-            _provider_state: &ProviderState,
-            _setup: bool,
-            _client: Option<&reqwest::Client>,
-        ) -> anyhow::Result<HashMap<String, Value>> {
-            Ok(HashMap::new())
-        }
-
-        fn teardown(self: &Self) -> bool {
-            true
-        }
-    }
-
-    let provider_state_executor = Arc::new(NoOpProviderState);
-
-    // Run the verifier against the pact file.
-    let result = pact_verifier::verify_provider_async(
-        provider_info,
-        vec![PactSource::File(pact_path.to_string_lossy().to_string())],
-        FilterInfo::None,
-        vec![],
-        &verification_options,
-        None,
-        &provider_state_executor,
-        None,
-    )
+        verify_messages,
+        provider_states: Arc::new(ShippingProviderStates::new(context)),
+    })
     .await
     .expect("Pact verification process errored");
 
     // Assert all interactions passed.
     assert!(result.result, "Pact verification failed, see output for details");
 
-    // Shut down the server task.
-    srv_handle.abort();
+    // Gracefully drain and await both servers.
+    guard.shutdown().await;
 }